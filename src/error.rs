@@ -0,0 +1,55 @@
+use std::fmt;
+
+/// errors produced while loading cursor themes or rendering cursor
+/// frames
+#[derive(Debug)]
+pub enum CursorError {
+	/// the requested theme (and everything is inherits from) could
+	/// not be found
+	ThemeNotFound(String),
+	/// the requested icon is not present in the theme
+	IconNotFound(String),
+	/// the svg cursor's `metadata.json` could not be parsed
+	MetadataParse(serde_json::Error),
+	/// the xcursor file is malformed or contains no images
+	XcursorParse,
+	/// rendering an svg cursor frame failed
+	Render,
+	/// an io error occurred while reading a theme file
+	Io(std::io::Error),
+}
+
+impl fmt::Display for CursorError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			CursorError::ThemeNotFound(name) => write!(f, "cursor theme `{name}` not found"),
+			CursorError::IconNotFound(name) => write!(f, "cursor icon `{name}` not found"),
+			CursorError::MetadataParse(err) => write!(f, "failed to parse cursor metadata: {err}"),
+			CursorError::XcursorParse => write!(f, "failed to parse xcursor file"),
+			CursorError::Render => write!(f, "failed to render cursor image"),
+			CursorError::Io(err) => write!(f, "io error: {err}"),
+		}
+	}
+}
+
+impl std::error::Error for CursorError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			CursorError::MetadataParse(err) => Some(err),
+			CursorError::Io(err) => Some(err),
+			_ => None,
+		}
+	}
+}
+
+impl From<std::io::Error> for CursorError {
+	fn from(err: std::io::Error) -> Self {
+		CursorError::Io(err)
+	}
+}
+
+impl From<serde_json::Error> for CursorError {
+	fn from(err: serde_json::Error) -> Self {
+		CursorError::MetadataParse(err)
+	}
+}
@@ -1,6 +1,10 @@
 //! a crate to load cursor themes, that supports both the xcursor format and
 //! the new kde svg cursor format.
 
+mod error;
+
+pub use error::CursorError;
+
 use resvg::{
 	tiny_skia::Pixmap,
 	usvg::{Transform, Tree},
@@ -8,11 +12,11 @@ use resvg::{
 use serde::Deserialize;
 use std::{
 	borrow::Cow,
-	collections::HashMap,
+	collections::{HashMap, HashSet},
 	ffi::{OsStr, OsString},
 	fmt::Debug,
 	path::{Path, PathBuf},
-	sync::{Arc, LazyLock},
+	sync::{Arc, LazyLock, Mutex},
 };
 
 fn xdg_data_dirs() -> Vec<PathBuf> {
@@ -47,6 +51,114 @@ static CURSOR_DIRS: LazyLock<Vec<PathBuf>> = LazyLock::new(|| {
 	user_dirs
 });
 
+fn xdg_config_home() -> PathBuf {
+	if let Some(config_home) = std::env::var_os("XDG_CONFIG_HOME") {
+		return PathBuf::from(config_home);
+	}
+
+	let home = std::env::var_os("HOME").expect("$HOME is not set");
+	PathBuf::from(home).join(".config")
+}
+
+/// reads a `key = value` pair out of an ini-style file, inside the
+/// given `[section]`
+fn ini_value(path: impl AsRef<Path>, section: &str, key: &str) -> Option<String> {
+	let content = std::fs::read_to_string(path).ok()?;
+
+	let mut current_section = String::new();
+	for line in content.lines() {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+			continue;
+		}
+
+		if let Some(name) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+			current_section = name.to_string();
+			continue;
+		}
+
+		if current_section != section {
+			continue;
+		}
+
+		let Some((k, v)) = line.split_once('=') else {
+			continue;
+		};
+
+		if k.trim() != key {
+			continue;
+		}
+
+		let value = v.trim().trim_matches('"');
+		if !value.is_empty() {
+			return Some(value.to_string());
+		}
+	}
+
+	None
+}
+
+/// reads the `Xcursor.theme` x resource out of `~/.Xresources` or
+/// `~/.Xdefaults`
+fn xresource_theme(home: &Path) -> Option<String> {
+	const KEY: &str = "Xcursor.theme";
+
+	for file in [".Xresources", ".Xdefaults"] {
+		let Ok(content) = std::fs::read_to_string(home.join(file)) else {
+			continue;
+		};
+
+		for line in content.lines() {
+			let line = line.trim();
+			let Some(rest) = line.strip_prefix(KEY) else {
+				continue;
+			};
+
+			let Some(value) = rest.trim_start().strip_prefix(':') else {
+				continue;
+			};
+
+			let value = value.trim();
+			if !value.is_empty() {
+				return Some(value.to_string());
+			}
+		}
+	}
+
+	None
+}
+
+/// detect the name of the cursor theme currently configured by the
+/// desktop environment
+///
+/// reads, in priority order, `kdeglobals` (`Icons`/`Theme`),
+/// `gtk-4.0/settings.ini` and `gtk-3.0/settings.ini`
+/// (`Settings`/`gtk-icon-theme-name`), and finally the
+/// `Xcursor.theme` x resource
+pub fn detect_theme_name() -> Option<String> {
+	let config_home = xdg_config_home();
+
+	ini_value(config_home.join("kdeglobals"), "Icons", "Theme")
+		.or_else(|| {
+			ini_value(
+				config_home.join("gtk-4.0/settings.ini"),
+				"Settings",
+				"gtk-icon-theme-name",
+			)
+		})
+		.or_else(|| {
+			ini_value(
+				config_home.join("gtk-3.0/settings.ini"),
+				"Settings",
+				"gtk-icon-theme-name",
+			)
+		})
+		.or_else(|| {
+			let home = std::env::var_os("HOME")?;
+			xresource_theme(Path::new(&home))
+		})
+}
+
 /// a cursor theme
 ///
 /// a cursor theme is a collection of cursor icons, in either the
@@ -62,26 +174,55 @@ impl CursorTheme {
 	/// this function loads all cursor icons into a cache, that
 	/// can be accessed through [`CursorTheme::icon`].
 	/// also searches through all themes this theme inherits from.
-	pub fn load(name: &str) -> Option<Self> {
+	///
+	/// # errors
+	///
+	/// returns [`CursorError::ThemeNotFound`] if neither the theme
+	/// nor any theme it inherits from could be found.
+	pub fn load(name: &str) -> Result<Self, CursorError> {
 		let mut cache = HashMap::new();
 		CursorTheme::discover(name, &mut cache);
 
 		if cache.is_empty() {
-			None
+			Err(CursorError::ThemeNotFound(name.to_owned()))
 		} else {
-			Some(CursorTheme { cache })
+			Ok(CursorTheme { cache })
 		}
 	}
 
+	/// attempts to load the cursor theme currently configured by the
+	/// desktop environment
+	///
+	/// resolves the active theme name via [`detect_theme_name`],
+	/// falling back to `"default"` if none of the known config files
+	/// set one.
+	///
+	/// # errors
+	///
+	/// returns [`CursorError::ThemeNotFound`] if the resolved theme
+	/// could not be found.
+	pub fn system() -> Result<Self, CursorError> {
+		let name = detect_theme_name().unwrap_or_else(|| "default".to_owned());
+		CursorTheme::load(&name)
+	}
+
 	fn discover(icon: &str, cache: &mut HashMap<OsString, Arc<Cursor>>) {
 		let mut stack = vec![Cow::Borrowed(icon)];
+		let mut walked_themes = HashSet::new();
 
 		while let Some(name) = stack.pop() {
+			if !walked_themes.insert(name.to_string()) {
+				continue;
+			}
+
 			let mut inherits = None;
+			let mut found = false;
 
 			for path in &*CURSOR_DIRS {
 				let path = path.join(&*name);
 				if path.is_dir() {
+					found = true;
+
 					let scalable = path.join("cursors_scalable");
 					if scalable.is_dir() {
 						CursorTheme::discover_svg_cursors(scalable, cache);
@@ -101,18 +242,41 @@ impl CursorTheme {
 				}
 			}
 
-			if let Some(it) = inherits {
-				stack.push(Cow::Owned(it));
+			// fall back to the `default` theme if the theme was found but
+			// had no `Inherits` key of its own, matching freedesktop
+			// behavior. a theme that wasn't found at all must not
+			// silently resolve to `default`.
+			let inherits = inherits.unwrap_or_else(|| {
+				if found && name != "default" {
+					vec!["default".to_owned()]
+				} else {
+					Vec::new()
+				}
+			});
+
+			// push in reverse so the first-listed inherited theme is
+			// popped (and thus discovered) before later ones, matching
+			// the "first listed takes priority" freedesktop semantics
+			for it in inherits.into_iter().rev() {
+				if !walked_themes.contains(&it) {
+					stack.push(Cow::Owned(it));
+				}
 			}
 		}
 	}
 
 	fn discover_svg_cursors(directory: PathBuf, cache: &mut HashMap<OsString, Arc<Cursor>>) {
-		CursorTheme::discover_cursors(directory, cache, |path| Cursor::Svg { path });
+		CursorTheme::discover_cursors(directory, cache, |path| Cursor::Svg {
+			path,
+			cache: FrameCache::default(),
+		});
 	}
 
 	fn discover_x_cursors(directory: PathBuf, cache: &mut HashMap<OsString, Arc<Cursor>>) {
-		CursorTheme::discover_cursors(directory, cache, |path| Cursor::X { path });
+		CursorTheme::discover_cursors(directory, cache, |path| Cursor::X {
+			path,
+			cache: FrameCache::default(),
+		});
 	}
 
 	fn discover_cursors<F>(directory: PathBuf, cache: &mut HashMap<OsString, Arc<Cursor>>, fun: F)
@@ -159,15 +323,55 @@ impl CursorTheme {
 	pub fn icon(&self, icon: &str) -> Option<&Cursor> {
 		self.cache.get::<OsStr>(icon.as_ref()).map(Arc::as_ref)
 	}
+
+	/// load an icon from the theme, falling back to caller-supplied
+	/// raw xcursor bytes if it (and its known aliases, e.g. `left_ptr`
+	/// / `default`) can't be found
+	///
+	/// mirrors wayland-cursor's `get_cursor_with_default_data`.
+	pub fn icon_or_fallback(
+		&self,
+		name: &str,
+		fallback: impl Fn(&CursorError) -> &'static [u8],
+	) -> Cursor {
+		if let Some(cursor) = self.icon(name) {
+			return cursor.clone();
+		}
+
+		if let Some(alias) = cursor_alias(name) {
+			if let Some(cursor) = self.icon(alias) {
+				return cursor.clone();
+			}
+		}
+
+		let err = CursorError::IconNotFound(name.to_owned());
+		let data = fallback(&err);
+		Cursor::Bytes {
+			data: data.to_vec(),
+			cache: FrameCache::default(),
+		}
+	}
+}
+
+/// standard cursor-name aliases, tried in [`CursorTheme::icon_or_fallback`]
+/// before giving up
+const CURSOR_ALIASES: &[(&str, &str)] = &[("left_ptr", "default"), ("text", "xterm")];
+
+fn cursor_alias(name: &str) -> Option<&'static str> {
+	CURSOR_ALIASES.iter().find_map(|&(a, b)| match name {
+		_ if name == a => Some(b),
+		_ if name == b => Some(a),
+		_ => None,
+	})
 }
 
-/// does the theme inherit from another theme?
+/// which themes does the theme inherit from?
 ///
 /// adapted from the [xcursor crate](https://github.com/esposm03/xcursor-rs)
-fn theme_inherits(path: PathBuf) -> Option<String> {
+fn theme_inherits(path: PathBuf) -> Option<Vec<String>> {
 	let content = std::fs::read_to_string(path).ok()?;
 
-	fn is_xcursor_space_or_separator(&ch: &char) -> bool {
+	fn is_xcursor_space_or_separator(ch: char) -> bool {
 		ch.is_whitespace() || ch == ';' || ch == ','
 	}
 
@@ -185,9 +389,11 @@ fn theme_inherits(path: PathBuf) -> Option<String> {
 		}
 
 		let inherits = chars
-			.skip_while(is_xcursor_space_or_separator)
-			.take_while(|ch| !is_xcursor_space_or_separator(ch))
-			.collect::<String>();
+			.as_str()
+			.split(is_xcursor_space_or_separator)
+			.filter(|it| !it.is_empty())
+			.map(str::to_owned)
+			.collect::<Vec<_>>();
 
 		if !inherits.is_empty() {
 			return Some(inherits);
@@ -197,73 +403,172 @@ fn theme_inherits(path: PathBuf) -> Option<String> {
 	None
 }
 
+/// already-decoded frames, cached by `(size, scale)`
+type FrameCache = Arc<Mutex<HashMap<(u32, u32), Arc<[Image]>>>>;
+
 /// a cursor icon
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Cursor {
 	/// an svg cursor icon
 	Svg {
 		/// the path to the directory of the svg
 		/// cursor icon
 		path: PathBuf,
+		/// cache of already-rendered frames
+		cache: FrameCache,
 	},
 	/// an xcursor icon
 	X {
 		/// the path to the xcursor file
 		path: PathBuf,
+		/// cache of already-parsed frames
+		cache: FrameCache,
+	},
+	/// an in-memory xcursor icon, e.g. produced by
+	/// [`CursorTheme::icon_or_fallback`]
+	Bytes {
+		/// the raw xcursor file contents
+		data: Vec<u8>,
+		/// cache of already-parsed frames
+		cache: FrameCache,
 	},
 }
 
 impl Cursor {
+	fn cache(&self) -> &FrameCache {
+		match self {
+			Cursor::Svg { cache, .. } | Cursor::X { cache, .. } | Cursor::Bytes { cache, .. } => {
+				cache
+			}
+		}
+	}
+
 	/// get cursor frames at the requested size
 	///
+	/// equivalent to [`Cursor::frames_scaled`] with a `scale` of `1`.
+	///
+	/// # errors
+	///
+	/// see [`Cursor::frames_scaled`].
+	pub fn frames(&self, size: u32) -> Result<Vec<Image>, CursorError> {
+		self.frames_scaled(size, 1)
+	}
+
+	/// get cursor frames at the requested size and output scale
+	///
 	/// - for [`CursorIcon::X`] icons this will return
-	///   the images that closest match the requested size.
-	///   
-	///   uses the [`xcursor`] crate for parsing xcursor files.
+	///   the images that closest match `size * scale`.
+	///
+	///   uses the [`xcursor`] crate for parsing xcursor files. files
+	///   larger than 64 KiB are memory mapped instead of being read
+	///   onto the heap; smaller files are read normally.
 	/// - for [`CursorIcon::Svg`] this will render
-	///   the images at the requested size.
-	///   
+	///   the images at `size * scale`.
+	///
 	///   for large images with many frames, this may take
 	///   a few seconds.
-	///   
+	///
 	///   uses the [`resvg`] crate for svg rendering.
-	pub fn frames(&self, size: u32) -> Option<Vec<Image>> {
-		match self {
-			Cursor::Svg { path } => {
+	///
+	/// in both cases [`Image::size`] still reports the logical `size`,
+	/// while the returned width, height and hotspot are in scaled
+	/// pixels, so a `wl_output` scale factor can be honored without
+	/// the caller having to do its own math.
+	///
+	/// decoded frames are cached by `(size, scale)`, so repeated calls
+	/// with the same arguments are cheap clones instead of re-parsing
+	/// or re-rendering.
+	///
+	/// # errors
+	///
+	/// returns [`CursorError::MetadataParse`] or
+	/// [`CursorError::XcursorParse`] if the underlying cursor file is
+	/// malformed, [`CursorError::Render`] if an svg frame could not be
+	/// rendered, and [`CursorError::Io`] if a file could not be read.
+	pub fn frames_scaled(&self, size: u32, scale: u32) -> Result<Vec<Image>, CursorError> {
+		let cache = self.cache();
+		if let Some(frames) = cache.lock().unwrap().get(&(size, scale)) {
+			return Ok(frames.to_vec());
+		}
+
+		let frames = match self {
+			Cursor::Svg { path, .. } => {
 				let metadata = path.join("metadata.json");
-				let metadata = std::fs::read_to_string(metadata).ok()?;
-				let metadata = serde_json::from_str::<Vec<Meta>>(&metadata).ok()?;
+				let metadata = std::fs::read_to_string(metadata)?;
+				let metadata = serde_json::from_str::<Vec<Meta>>(&metadata)?;
 
 				if metadata.is_empty() {
-					return None;
+					return Err(CursorError::Render);
 				}
 
 				metadata
 					.into_iter()
-					.map(|meta| Image::render_svg(path, size, meta))
-					.collect()
+					.map(|meta| Image::render_svg(path, size, scale, meta))
+					.collect::<Result<Vec<_>, _>>()?
 			}
-			Cursor::X { path } => {
-				let content = std::fs::read(path).ok()?;
-				let images = xcursor::parser::parse_xcursor(&content)?;
-
-				let nearest = images
-					.iter()
-					.min_by_key(|img| u32::abs_diff(img.size, size))?;
-				let nearest_size = nearest.size;
-
-				let frames = images
-					.into_iter()
-					.filter(|img| img.size == nearest_size)
-					.map(Image::from_xcursor)
-					.collect();
-				Some(frames)
+			Cursor::X { path, .. } => {
+				let file = std::fs::File::open(path)?;
+				let len = file.metadata()?.len();
+
+				if len > MMAP_THRESHOLD {
+					// SAFETY: the theme file is not expected to be
+					// truncated or rewritten while mapped. that
+					// invariant isn't actually enforced by anything
+					// (a package manager upgrade or a user edit could
+					// race this read), in which case the process may
+					// receive SIGBUS instead of a `CursorError`. this
+					// is accepted only for files above `MMAP_THRESHOLD`,
+					// to avoid copying large multi-size xcursor files
+					// onto the heap, matching linicon's behavior; small
+					// files are read normally below.
+					let mmap = unsafe { memmap2::Mmap::map(&file)? };
+					xcursor_frames(&mmap, size, scale)?
+				} else {
+					let content = std::fs::read(path)?;
+					xcursor_frames(&content, size, scale)?
+				}
 			}
-		}
+			Cursor::Bytes { data, .. } => xcursor_frames(data, size, scale)?,
+		};
+
+		cache
+			.lock()
+			.unwrap()
+			.insert((size, scale), Arc::from(frames.clone()));
+		Ok(frames)
 	}
 }
 
+/// xcursor files at or below this size are read onto the heap instead
+/// of memory-mapped, to avoid mapping small files that gain little
+/// from it while still being exposed to the file being modified
+/// concurrently
+const MMAP_THRESHOLD: u64 = 64 * 1024;
+
+/// parse raw xcursor bytes and pick the frames closest to `size * scale`
+fn xcursor_frames(content: &[u8], size: u32, scale: u32) -> Result<Vec<Image>, CursorError> {
+	let images = xcursor::parser::parse_xcursor(content).ok_or(CursorError::XcursorParse)?;
+	let target = size.saturating_mul(scale);
+
+	let nearest = images
+		.iter()
+		.min_by_key(|img| u32::abs_diff(img.size, target))
+		.ok_or(CursorError::XcursorParse)?;
+	let nearest_size = nearest.size;
+
+	let frames = images
+		.into_iter()
+		.filter(|img| img.size == nearest_size)
+		.map(|img| Image {
+			size,
+			..Image::from_xcursor(img)
+		})
+		.collect();
+	Ok(frames)
+}
+
 /// a cursor image
+#[derive(Clone)]
 pub struct Image {
 	/// the nominal size of the image
 	pub size: u32,
@@ -319,21 +624,22 @@ impl Image {
 	/// render svg cursors to the requested size
 	///
 	/// https://invent.kde.org/plasma/breeze/-/blob/master/cursors/svg-cursor-format.schema.json
-	fn render_svg(path: &Path, size: u32, meta: Meta) -> Option<Self> {
+	fn render_svg(path: &Path, size: u32, scale: u32, meta: Meta) -> Result<Self, CursorError> {
 		let usvg_opts = resvg::usvg::Options::default();
 
 		let data = path.join(meta.filename);
-		let data = std::fs::read(data).ok()?;
+		let data = std::fs::read(data)?;
 
-		let tree = Tree::from_data(&data, &usvg_opts).ok()?;
+		let tree = Tree::from_data(&data, &usvg_opts).map_err(|_| CursorError::Render)?;
 
-		let scale = size as f32 / meta.nominal_size;
+		let physical_size = size.checked_mul(scale).ok_or(CursorError::Render)?;
+		let scale = physical_size as f32 / meta.nominal_size;
 		let transform = Transform::from_scale(scale, scale);
 
 		let width = (tree.size().width() * scale) as u32;
 		let height = (tree.size().height() * scale) as u32;
 
-		let mut pixmap = Pixmap::new(width, height)?;
+		let mut pixmap = Pixmap::new(width, height).ok_or(CursorError::Render)?;
 		resvg::render(&tree, transform, &mut pixmap.as_mut());
 
 		let image = Image {
@@ -348,7 +654,7 @@ impl Image {
 
 			pixels: pixmap.take(),
 		};
-		Some(image)
+		Ok(image)
 	}
 }
 